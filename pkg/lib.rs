@@ -55,9 +55,125 @@ struct MathResult {
     subexpressions: Vec<Subexpression>,
 }
 
+/// Syntactic kind of a node in the hierarchical subexpression tree. Mirrors
+/// the `Expr` variants `collect_expr_spans`/`MathVisitor` recurse into, plus
+/// `Leaf`/`Other` for terminal and not-specially-handled expressions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum NodeKind {
+    Math,
+    Frac,
+    Attach,
+    Primes,
+    Root,
+    Delimited,
+    FuncCall,
+    Parenthesized,
+    Array,
+    Dict,
+    ContentBlock,
+    Binary,
+    Unary,
+    Ident,
+    Leaf,
+    Other,
+}
+
+/// A node in the hierarchical subexpression tree, with enough information
+/// to reconstruct both the tree shape (`children`) and the flat rendering
+/// (`x`/`y`/`width`/`height`) a client already gets from `Subexpression`.
+#[derive(Serialize, Deserialize)]
+struct TreeNode {
+    id: usize,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    kind: NodeKind,
+    text: String,
+    x: Option<f64>,
+    y: Option<f64>,
+    width: Option<f64>,
+    height: Option<f64>,
+    source_start: Option<usize>,
+    source_end: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TreeResult {
+    svg: String,
+    nodes: Vec<TreeNode>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Severity {
+    Error,
+    Warning,
+}
+
+/// A single diagnostic with enough source position information for the
+/// frontend to underline the offending characters in the math input.
+#[derive(Serialize, Deserialize)]
+struct Diagnostic {
+    severity: Severity,
+    message: String,
+    source_start: Option<usize>,
+    source_end: Option<usize>,
+    hints: Vec<String>,
+}
+
+impl Diagnostic {
+    /// A diagnostic that has no associated source range, e.g. one raised
+    /// before spans have been numberized.
+    fn detached(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            source_start: None,
+            source_end: None,
+            hints: Vec::new(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct ErrorResult {
-    error: String,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl ErrorResult {
+    fn single(diagnostic: Diagnostic) -> Self {
+        Self { diagnostics: vec![diagnostic] }
+    }
+}
+
+/// Resolve a [`typst::syntax::SyntaxError`] (raised during parsing) into a
+/// span-tagged [`Diagnostic`], mirroring how [`collect_expr_spans`] resolves
+/// `Expr` spans to source ranges via `world.range`.
+fn diagnostic_from_syntax_error(error: &typst::syntax::SyntaxError, world: &dyn World) -> Diagnostic {
+    let range = world.range(error.span);
+    Diagnostic {
+        severity: Severity::Error,
+        message: error.message.to_string(),
+        source_start: range.as_ref().map(|r| r.start),
+        source_end: range.as_ref().map(|r| r.end),
+        hints: error.hints.iter().map(|hint| hint.to_string()).collect(),
+    }
+}
+
+/// Resolve a [`typst::diag::SourceDiagnostic`] (raised during eval or layout)
+/// into a span-tagged [`Diagnostic`].
+fn diagnostic_from_source_diagnostic(error: &typst::diag::SourceDiagnostic, world: &dyn World) -> Diagnostic {
+    let range = world.range(error.span);
+    Diagnostic {
+        severity: match error.severity {
+            typst::diag::Severity::Error => Severity::Error,
+            typst::diag::Severity::Warning => Severity::Warning,
+        },
+        message: error.message.to_string(),
+        source_start: range.as_ref().map(|r| r.start),
+        source_end: range.as_ref().map(|r| r.end),
+        hints: error.hints.iter().map(|hint| hint.to_string()).collect(),
+    }
 }
 
 #[wasm_bindgen]
@@ -280,6 +396,208 @@ fn collect_expr_spans(expr: typst::syntax::ast::Expr, source_text: &str, world:
     }
 }
 
+/// Walks a math `Expr` tree, threading a parent node id through the
+/// recursion so implementors can build a hierarchical structure instead of
+/// the flat list `collect_expr_spans` produces. `visit_expr` names every
+/// math-relevant variant explicitly; anything else (including a future
+/// `Expr` variant we haven't special-cased) falls through to `visit_other`.
+trait MathVisitor {
+    /// Register a node for `span` under `parent` and return its id.
+    fn push_node(&mut self, kind: NodeKind, span: Span, parent: Option<usize>) -> usize;
+
+    fn visit_expr(&mut self, expr: typst::syntax::ast::Expr, parent: Option<usize>) -> usize {
+        use typst::syntax::ast::Expr;
+
+        match expr {
+            Expr::Math(math) => self.visit_math(math, parent),
+            Expr::MathFrac(frac) => self.visit_frac(frac, parent),
+            Expr::MathAttach(attach) => self.visit_attach(attach, parent),
+            Expr::MathPrimes(primes) => self.visit_primes(primes, parent),
+            Expr::MathRoot(root) => self.visit_root(root, parent),
+            Expr::MathDelimited(delim) => self.visit_delimited(delim, parent),
+            Expr::FuncCall(call) => self.visit_func_call(call, parent),
+            Expr::Parenthesized(paren) => self.visit_parenthesized(paren, parent),
+            Expr::Array(array) => self.visit_array(array, parent),
+            Expr::Dict(dict) => self.visit_dict(dict, parent),
+            Expr::ContentBlock(block) => self.visit_content_block(block, parent),
+            Expr::Binary(binary) => self.visit_binary(binary, parent),
+            Expr::Unary(unary) => self.visit_unary(unary, parent),
+            Expr::MathIdent(ident) => self.visit_math_ident(ident, parent),
+            Expr::Ident(ident) => self.visit_ident(ident, parent),
+            Expr::MathShorthand(_)
+            | Expr::MathAlignPoint(_)
+            | Expr::Text(_)
+            | Expr::Str(_)
+            | Expr::Int(_)
+            | Expr::Float(_)
+            | Expr::Bool(_)
+            | Expr::None(_)
+            | Expr::Auto(_) => self.visit_leaf(expr, parent),
+            other => self.visit_other(other, parent),
+        }
+    }
+
+    fn visit_math(&mut self, math: typst::syntax::ast::Math, parent: Option<usize>) -> usize {
+        let id = self.push_node(NodeKind::Math, math.span(), parent);
+        for child in math.exprs() {
+            self.visit_expr(child, Some(id));
+        }
+        id
+    }
+
+    fn visit_frac(&mut self, frac: typst::syntax::ast::MathFrac, parent: Option<usize>) -> usize {
+        let id = self.push_node(NodeKind::Frac, frac.span(), parent);
+        self.visit_expr(frac.num(), Some(id));
+        self.visit_expr(frac.denom(), Some(id));
+        id
+    }
+
+    fn visit_attach(&mut self, attach: typst::syntax::ast::MathAttach, parent: Option<usize>) -> usize {
+        let id = self.push_node(NodeKind::Attach, attach.span(), parent);
+        self.visit_expr(attach.base(), Some(id));
+        if let Some(bottom) = attach.bottom() {
+            self.visit_expr(bottom, Some(id));
+        }
+        if let Some(top) = attach.top() {
+            self.visit_expr(top, Some(id));
+        }
+        id
+    }
+
+    fn visit_primes(&mut self, primes: typst::syntax::ast::MathPrimes, parent: Option<usize>) -> usize {
+        // Primes have no base of their own; the span is the whole node.
+        self.push_node(NodeKind::Primes, primes.span(), parent)
+    }
+
+    fn visit_root(&mut self, root: typst::syntax::ast::MathRoot, parent: Option<usize>) -> usize {
+        let id = self.push_node(NodeKind::Root, root.span(), parent);
+        self.visit_expr(root.radicand(), Some(id));
+        id
+    }
+
+    fn visit_delimited(&mut self, delim: typst::syntax::ast::MathDelimited, parent: Option<usize>) -> usize {
+        let id = self.push_node(NodeKind::Delimited, delim.span(), parent);
+        for child in delim.body().exprs() {
+            self.visit_expr(child, Some(id));
+        }
+        id
+    }
+
+    fn visit_func_call(&mut self, call: typst::syntax::ast::FuncCall, parent: Option<usize>) -> usize {
+        let id = self.push_node(NodeKind::FuncCall, call.span(), parent);
+        self.visit_expr(call.callee(), Some(id));
+        for arg in call.args().items() {
+            if let typst::syntax::ast::Arg::Pos(expr) = arg {
+                self.visit_expr(expr, Some(id));
+            }
+        }
+        id
+    }
+
+    fn visit_parenthesized(&mut self, paren: typst::syntax::ast::Parenthesized, parent: Option<usize>) -> usize {
+        let id = self.push_node(NodeKind::Parenthesized, paren.span(), parent);
+        self.visit_expr(paren.expr(), Some(id));
+        id
+    }
+
+    fn visit_array(&mut self, array: typst::syntax::ast::Array, parent: Option<usize>) -> usize {
+        let id = self.push_node(NodeKind::Array, array.span(), parent);
+        for item in array.items() {
+            match item {
+                typst::syntax::ast::ArrayItem::Pos(expr) => {
+                    self.visit_expr(expr, Some(id));
+                }
+                typst::syntax::ast::ArrayItem::Spread(spread) => {
+                    self.visit_expr(spread.expr(), Some(id));
+                }
+            }
+        }
+        id
+    }
+
+    fn visit_dict(&mut self, dict: typst::syntax::ast::Dict, parent: Option<usize>) -> usize {
+        let id = self.push_node(NodeKind::Dict, dict.span(), parent);
+        for item in dict.items() {
+            match item {
+                typst::syntax::ast::DictItem::Named(named) => {
+                    self.visit_expr(named.expr(), Some(id));
+                }
+                typst::syntax::ast::DictItem::Keyed(keyed) => {
+                    self.visit_expr(keyed.key(), Some(id));
+                    self.visit_expr(keyed.expr(), Some(id));
+                }
+                typst::syntax::ast::DictItem::Spread(spread) => {
+                    self.visit_expr(spread.expr(), Some(id));
+                }
+            }
+        }
+        id
+    }
+
+    fn visit_content_block(&mut self, block: typst::syntax::ast::ContentBlock, parent: Option<usize>) -> usize {
+        let id = self.push_node(NodeKind::ContentBlock, block.span(), parent);
+        for expr in block.body().exprs() {
+            self.visit_expr(expr, Some(id));
+        }
+        id
+    }
+
+    fn visit_binary(&mut self, binary: typst::syntax::ast::Binary, parent: Option<usize>) -> usize {
+        let id = self.push_node(NodeKind::Binary, binary.span(), parent);
+        self.visit_expr(binary.lhs(), Some(id));
+        self.visit_expr(binary.rhs(), Some(id));
+        id
+    }
+
+    fn visit_unary(&mut self, unary: typst::syntax::ast::Unary, parent: Option<usize>) -> usize {
+        let id = self.push_node(NodeKind::Unary, unary.span(), parent);
+        self.visit_expr(unary.expr(), Some(id));
+        id
+    }
+
+    fn visit_math_ident(&mut self, ident: typst::syntax::ast::MathIdent, parent: Option<usize>) -> usize {
+        self.push_node(NodeKind::Ident, ident.span(), parent)
+    }
+
+    fn visit_ident(&mut self, ident: typst::syntax::ast::Ident, parent: Option<usize>) -> usize {
+        self.push_node(NodeKind::Ident, ident.span(), parent)
+    }
+
+    /// Leaf math syntax with no children of its own (shorthands, align
+    /// points, literals).
+    fn visit_leaf(&mut self, expr: typst::syntax::ast::Expr, parent: Option<usize>) -> usize {
+        self.push_node(NodeKind::Leaf, expr.span(), parent)
+    }
+
+    /// Expressions outside the math-relevant subset above. `parse_math`
+    /// only ever produces the variants handled explicitly, but we still
+    /// record a node here rather than dropping the span.
+    fn visit_other(&mut self, expr: typst::syntax::ast::Expr, parent: Option<usize>) -> usize {
+        self.push_node(NodeKind::Other, expr.span(), parent)
+    }
+}
+
+struct RawNode {
+    parent: Option<usize>,
+    kind: NodeKind,
+    span: Span,
+}
+
+/// Collects `RawNode`s in id order (a node's id is its index into `nodes`)
+/// while visiting a math AST.
+#[derive(Default)]
+struct TreeBuilder {
+    nodes: Vec<RawNode>,
+}
+
+impl MathVisitor for TreeBuilder {
+    fn push_node(&mut self, kind: NodeKind, span: Span, parent: Option<usize>) -> usize {
+        let id = self.nodes.len();
+        self.nodes.push(RawNode { parent, kind, span });
+        id
+    }
+}
+
 fn normalize_bbox(bbox: typst::layout::Rect, offset: Point) -> BoundingBox {
     // Text uses Y-up, frames use Y-down - flip Y coordinates
     let min = bbox.min;
@@ -375,9 +693,86 @@ fn extract_subexpressions(
     results
 }
 
-/// Compile pure math expression and return both SVG and subexpression data as JSON
-#[wasm_bindgen]
-pub fn compile_math_with_subexpressions(input: &str) -> String {
+/// Extract the hierarchical subexpression tree from the AST, with bounding
+/// boxes resolved from the frame the same way `extract_subexpressions` does.
+fn extract_tree(
+    ast: &typst::syntax::ast::Math,
+    source_text: &str,
+    frame: &Frame,
+    world: &dyn World,
+    offset: Point,
+) -> Vec<TreeNode> {
+    let mut builder = TreeBuilder::default();
+    builder.visit_math(*ast, None);
+    let raw_nodes = builder.nodes;
+
+    let mut box_spans = Vec::new();
+    get_frame_box_spans(frame, world, offset, &mut box_spans);
+
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); raw_nodes.len()];
+    for (id, node) in raw_nodes.iter().enumerate() {
+        if let Some(parent) = node.parent {
+            children[parent].push(id);
+        }
+    }
+
+    raw_nodes
+        .into_iter()
+        .enumerate()
+        .map(|(id, node)| {
+            let range = world.range(node.span);
+            let text = range
+                .as_ref()
+                .filter(|r| r.end <= source_text.len())
+                .map(|r| source_text[r.start..r.end].to_string())
+                .unwrap_or_default();
+
+            let mut bounding_box = None;
+            if let Some(range) = &range {
+                for (sub_span, sub_bounding_box) in &box_spans {
+                    if !(sub_span.start >= range.start && sub_span.end <= range.end) {
+                        continue;
+                    }
+                    bounding_box = Some(bounding_box.map_or(*sub_bounding_box, |v: BoundingBox| v.merge(*sub_bounding_box)));
+                }
+            }
+
+            TreeNode {
+                id,
+                parent: node.parent,
+                children: std::mem::take(&mut children[id]),
+                kind: node.kind,
+                text,
+                x: bounding_box.map(|b| b.x0),
+                y: bounding_box.map(|b| b.y0),
+                width: bounding_box.map(|b| b.x1 - b.x0),
+                height: bounding_box.map(|b| b.y1 - b.y0),
+                source_start: range.as_ref().map(|r| r.start),
+                source_end: range.as_ref().map(|r| r.end),
+            }
+        })
+        .collect()
+}
+
+fn error_json(error: ErrorResult) -> String {
+    serde_json::to_string(&error).unwrap_or_else(|_| r#"{"error":"JSON serialization failed"}"#.to_string())
+}
+
+/// The shared result of parsing, evaluating and laying out a pure math
+/// input. Factored out of `compile_math_with_subexpressions` so the other
+/// `compile_math_with_*` entry points don't have to re-run the same
+/// parse/eval/layout pipeline.
+struct CompiledMath {
+    world: SimpleWorld,
+    math_ast: typst::syntax::ast::Math,
+    frame: Frame,
+}
+
+/// Parse `input` as pure math and numberize its spans, without evaluating
+/// or laying it out. Shared by `compile_math` (which goes on to eval/layout
+/// for rendering) and entry points that only need the parsed structure,
+/// such as the term IR export.
+fn parse_math_ast(input: &str) -> Result<(SimpleWorld, typst::syntax::ast::Math), ErrorResult> {
     // Create FileId first
     let file_id = FileId::new(None, VirtualPath::new("math.typ"));
 
@@ -386,42 +781,36 @@ pub fn compile_math_with_subexpressions(input: &str) -> String {
 
     // Numberize the math tree to link spans to FileId
     if let Err(_) = root.numberize(file_id, Span::FULL) {
-        return serde_json::to_string(&ErrorResult {
-            error: "Failed to numberize spans".to_string(),
-        })
-        .unwrap_or_else(|_| r#"{"error":"JSON serialization failed"}"#.to_string());
+        return Err(ErrorResult::single(Diagnostic::detached("Failed to numberize spans")));
     }
 
+    // Create a Source with the numberized math tree (not markup tree)
+    // This ensures source.range(span) can find spans in the math tree
+    let source = Source::from_root(file_id, input.to_string(), root.clone());
+    let world = SimpleWorld::new(source);
+
     // Check for parse errors
     let errors = root.errors();
     if !errors.is_empty() {
-        let error_msg = errors
+        let diagnostics = errors
             .iter()
-            .map(|e| format!("{}", e.message))
-            .collect::<Vec<_>>()
-            .join("\n");
-        return serde_json::to_string(&ErrorResult {
-            error: format!("Parse error: {}", error_msg),
-        })
-        .unwrap_or_else(|_| r#"{"error":"JSON serialization failed"}"#.to_string());
+            .map(|e| diagnostic_from_syntax_error(e, world.upcast()))
+            .collect();
+        return Err(ErrorResult { diagnostics });
     }
 
     let math_ast1 = root.clone();
     let math_ast = match math_ast1.cast::<typst::syntax::ast::Math>() {
         Some(math) => math,
-        None => {
-            return serde_json::to_string(&ErrorResult {
-                error: "Failed to cast to Math".to_string(),
-            })
-            .unwrap_or_else(|_| r#"{"error":"JSON serialization failed"}"#.to_string());
-        }
+        None => return Err(ErrorResult::single(Diagnostic::detached("Failed to cast to Math"))),
     };
 
-    // Create a Source with the numberized math tree (not markup tree)
-    // This ensures source.range(span) can find spans in the math tree
-    let source = Source::from_root(file_id, input.to_string(), root);
+    Ok((world, math_ast))
+}
 
-    let world = SimpleWorld::new(source);
+/// Parse, evaluate and lay out `input` as pure math.
+fn compile_math(input: &str) -> Result<CompiledMath, ErrorResult> {
+    let (world, math_ast) = parse_math_ast(input)?;
 
     // Setup engine and VM for evaluation
     let introspector = Introspector::default();
@@ -445,15 +834,11 @@ pub fn compile_math_with_subexpressions(input: &str) -> String {
     let math_content = match math_ast.eval(&mut vm) {
         Ok(content) => content,
         Err(errors) => {
-            let error_msg = errors
+            let diagnostics = errors
                 .iter()
-                .map(|e| format!("{}", e.message))
-                .collect::<Vec<_>>()
-                .join("\n");
-            return serde_json::to_string(&ErrorResult {
-                error: format!("Eval error: {}", error_msg),
-            })
-            .unwrap_or_else(|_| r#"{"error":"JSON serialization failed"}"#.to_string());
+                .map(|e| diagnostic_from_source_diagnostic(e, world.upcast()))
+                .collect();
+            return Err(ErrorResult { diagnostics });
         }
     };
 
@@ -464,12 +849,7 @@ pub fn compile_math_with_subexpressions(input: &str) -> String {
 
     let equation_elem = match equation.to_packed::<EquationElem>() {
         Some(elem) => elem,
-        None => {
-            return serde_json::to_string(&ErrorResult {
-                error: "Failed to pack equation".to_string(),
-            })
-            .unwrap_or_else(|_| r#"{"error":"JSON serialization failed"}"#.to_string());
-        }
+        None => return Err(ErrorResult::single(Diagnostic::detached("Failed to pack equation"))),
     };
 
     // Layout the equation to get frame
@@ -491,27 +871,39 @@ pub fn compile_math_with_subexpressions(input: &str) -> String {
     let fragment = match layout_equation_block(&equation_elem, &mut vm.engine, locator, styles, regions) {
         Ok(fragment) => fragment,
         Err(errors) => {
-            let error_msg = errors
+            let diagnostics = errors
                 .iter()
-                .map(|e| format!("{}", e.message))
-                .collect::<Vec<_>>()
-                .join("\n");
-            return serde_json::to_string(&ErrorResult {
-                error: format!("Layout error: {}", error_msg),
-            })
-            .unwrap_or_else(|_| r#"{"error":"JSON serialization failed"}"#.to_string());
+                .map(|e| diagnostic_from_source_diagnostic(e, world.upcast()))
+                .collect();
+            return Err(ErrorResult { diagnostics });
         }
     };
 
     // Combine all frames into a single frame
     let frame = fragment.into_frame();
 
+    Ok(CompiledMath { world, math_ast, frame })
+}
+
+/// Compile pure math expression and return both SVG and subexpression data as JSON
+#[wasm_bindgen]
+pub fn compile_math_with_subexpressions(input: &str) -> String {
+    let compiled = match compile_math(input) {
+        Ok(compiled) => compiled,
+        Err(error) => return error_json(error),
+    };
 
     // Extract subexpressions from AST with bounding boxes from frame
-    let subexpressions = extract_subexpressions(&math_ast, input, &frame, world.upcast(), Point::zero());
+    let subexpressions = extract_subexpressions(
+        &compiled.math_ast,
+        input,
+        &compiled.frame,
+        compiled.world.upcast(),
+        Point::zero(),
+    );
 
     // Convert frame to SVG
-    let svg = typst_svg::svg_frame(&frame);
+    let svg = typst_svg::svg_frame(&compiled.frame);
 
     // Build JSON response using serde
     serde_json::to_string(&MathResult {
@@ -520,3 +912,885 @@ pub fn compile_math_with_subexpressions(input: &str) -> String {
     })
     .unwrap_or_else(|_| r#"{"error":"JSON serialization failed"}"#.to_string())
 }
+
+/// Compile pure math expression and return SVG plus a hierarchical
+/// subexpression tree (parent/child structure and syntactic kind), instead
+/// of the flat list `compile_math_with_subexpressions` returns. This lets a
+/// caller select "the whole numerator" or "the enclosing binary operation"
+/// by id rather than having to re-derive nesting from overlapping ranges.
+#[wasm_bindgen]
+pub fn compile_math_with_tree(input: &str) -> String {
+    let compiled = match compile_math(input) {
+        Ok(compiled) => compiled,
+        Err(error) => return error_json(error),
+    };
+
+    let nodes = extract_tree(
+        &compiled.math_ast,
+        input,
+        &compiled.frame,
+        compiled.world.upcast(),
+        Point::zero(),
+    );
+
+    let svg = typst_svg::svg_frame(&compiled.frame);
+
+    serde_json::to_string(&TreeResult { svg, nodes })
+        .unwrap_or_else(|_| r#"{"error":"JSON serialization failed"}"#.to_string())
+}
+
+/// A node in the lowered term IR. Every node carries the source range it
+/// was lowered from so it round-trips back to the bounding boxes produced
+/// by `extract_subexpressions`/`extract_tree`.
+#[derive(Serialize, Deserialize)]
+struct Term {
+    source_start: Option<usize>,
+    source_end: Option<usize>,
+    #[serde(flatten)]
+    kind: TermKind,
+}
+
+impl Term {
+    fn new(kind: TermKind, span: Span, world: &dyn World) -> Term {
+        let range = world.range(span);
+        Term {
+            source_start: range.as_ref().map(|r| r.start),
+            source_end: range.as_ref().map(|r| r.end),
+            kind,
+        }
+    }
+}
+
+/// The shape of a lowered math expression, independent of its source range.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind")]
+enum TermKind {
+    Ident { name: String },
+    Num { value: String },
+    Frac { num: Box<Term>, denom: Box<Term> },
+    Attach { base: Box<Term>, sub: Option<Box<Term>>, sup: Option<Box<Term>> },
+    Root { index: Option<u8>, radicand: Box<Term> },
+    Apply { head: Box<Term>, args: Vec<Term> },
+    BinOp { op: String, lhs: Box<Term>, rhs: Box<Term> },
+    Group { delim: GroupDelim, items: Vec<Term> },
+    /// Math syntax that doesn't fit the variants above (shorthands, align
+    /// points, string/bool/none/auto literals, primes) but still needs a
+    /// node so no part of the tree is silently dropped.
+    Leaf { text: String },
+}
+
+/// How a `TermKind::Group` was delimited in the source, so e.g. `|a+b|` and
+/// `(a+b)` don't lower to the same indistinguishable term.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind")]
+enum GroupDelim {
+    /// A bare run of expressions with no delimiter of its own
+    /// (`Expr::Math`, `Expr::ContentBlock`).
+    None,
+    /// Matched delimiter tokens read from the source, e.g. `(`/`)` or `|`/`|`.
+    Matched { open: String, close: String },
+    Array,
+    Dict,
+}
+
+/// Read the literal source text a span covers, for the handful of `Term`
+/// leaves whose content isn't otherwise reconstructible (e.g. shorthands).
+fn span_text(span: Span, world: &dyn World) -> String {
+    let Some(range) = world.range(span) else { return String::new() };
+    let Ok(source) = world.source(world.main()) else { return String::new() };
+    let text = source.text();
+    if range.end <= text.len() { text[range.start..range.end].to_string() } else { String::new() }
+}
+
+/// Lower a single math `Expr` into a `Term`, recursing into its children.
+/// Returns `None` for expression kinds that don't arise inside pure math
+/// input (`parse_math`'s output), mirroring `collect_expr_spans`'s math-only
+/// scope.
+fn lower_expr(expr: typst::syntax::ast::Expr, world: &dyn World) -> Option<Term> {
+    use typst::syntax::ast::{Arg, ArrayItem, DictItem, Expr};
+
+    let span = expr.span();
+    let kind = match expr {
+        Expr::Math(math) => TermKind::Group {
+            delim: GroupDelim::None,
+            items: math.exprs().filter_map(|e| lower_expr(e, world)).collect(),
+        },
+        Expr::MathFrac(frac) => TermKind::Frac {
+            num: Box::new(lower_expr(frac.num(), world)?),
+            denom: Box::new(lower_expr(frac.denom(), world)?),
+        },
+        Expr::MathAttach(attach) => TermKind::Attach {
+            base: Box::new(lower_expr(attach.base(), world)?),
+            sub: attach.bottom().and_then(|e| lower_expr(e, world)).map(Box::new),
+            sup: attach.top().and_then(|e| lower_expr(e, world)).map(Box::new),
+        },
+        Expr::MathRoot(root) => TermKind::Root {
+            index: root.index(),
+            radicand: Box::new(lower_expr(root.radicand(), world)?),
+        },
+        Expr::MathDelimited(delim) => TermKind::Group {
+            delim: GroupDelim::Matched {
+                open: span_text(delim.open().span(), world),
+                close: span_text(delim.close().span(), world),
+            },
+            items: delim.body().exprs().filter_map(|e| lower_expr(e, world)).collect(),
+        },
+        Expr::ContentBlock(block) => TermKind::Group {
+            delim: GroupDelim::None,
+            items: block.body().exprs().filter_map(|e| lower_expr(e, world)).collect(),
+        },
+        Expr::Parenthesized(paren) => TermKind::Group {
+            delim: GroupDelim::Matched { open: "(".to_string(), close: ")".to_string() },
+            items: vec![lower_expr(paren.expr(), world)?],
+        },
+        Expr::Array(array) => TermKind::Group {
+            delim: GroupDelim::Array,
+            items: array
+                .items()
+                .filter_map(|item| match item {
+                    ArrayItem::Pos(expr) => lower_expr(expr, world),
+                    ArrayItem::Spread(spread) => lower_expr(spread.expr(), world),
+                })
+                .collect(),
+        },
+        Expr::Dict(dict) => TermKind::Group {
+            delim: GroupDelim::Dict,
+            items: dict
+                .items()
+                .filter_map(|item| match item {
+                    DictItem::Named(named) => lower_expr(named.expr(), world),
+                    DictItem::Keyed(keyed) => lower_expr(keyed.expr(), world),
+                    DictItem::Spread(spread) => lower_expr(spread.expr(), world),
+                })
+                .collect(),
+        },
+        Expr::FuncCall(call) => TermKind::Apply {
+            head: Box::new(lower_expr(call.callee(), world)?),
+            args: call
+                .args()
+                .items()
+                .filter_map(|arg| match arg {
+                    Arg::Pos(expr) => lower_expr(expr, world),
+                    _ => None,
+                })
+                .collect(),
+        },
+        Expr::Binary(binary) => TermKind::BinOp {
+            op: binary.op().as_str().to_string(),
+            lhs: Box::new(lower_expr(binary.lhs(), world)?),
+            rhs: Box::new(lower_expr(binary.rhs(), world)?),
+        },
+        Expr::Unary(unary) => {
+            // The operator token is the unary node's first child; using
+            // `span` (the whole `-x`) here would make the synthesized
+            // Ident highlight the operand too.
+            let op_span = unary.to_untyped().children().next().map_or(span, |node| node.span());
+            TermKind::Apply {
+                head: Box::new(Term::new(
+                    TermKind::Ident { name: unary.op().as_str().to_string() },
+                    op_span,
+                    world,
+                )),
+                args: vec![lower_expr(unary.expr(), world)?],
+            }
+        }
+        Expr::MathIdent(ident) => TermKind::Ident { name: ident.as_str().to_string() },
+        Expr::Ident(ident) => TermKind::Ident { name: ident.as_str().to_string() },
+        Expr::Int(v) => TermKind::Num { value: v.get().to_string() },
+        Expr::Float(v) => TermKind::Num { value: v.get().to_string() },
+        Expr::MathPrimes(_)
+        | Expr::MathShorthand(_)
+        | Expr::MathAlignPoint(_)
+        | Expr::Text(_)
+        | Expr::Str(_)
+        | Expr::Bool(_)
+        | Expr::None(_)
+        | Expr::Auto(_) => TermKind::Leaf { text: span_text(span, world) },
+        _ => return None,
+    };
+
+    Some(Term::new(kind, span, world))
+}
+
+#[derive(Serialize)]
+struct TermResult {
+    term: Option<Term>,
+}
+
+/// Lower pure math `input` into a self-contained, serde-serializable term
+/// IR (see `Term`/`TermKind`), rather than the rendered SVG plus text/bbox
+/// fragments the other `compile_math_with_*` entry points return. This
+/// gives external proof tooling a stable surface to rewrite, match and
+/// reconstruct expressions against, keyed by source range.
+#[wasm_bindgen]
+pub fn compile_math_to_term(input: &str) -> String {
+    let (world, math_ast) = match parse_math_ast(input) {
+        Ok(parsed) => parsed,
+        Err(error) => return error_json(error),
+    };
+
+    let term = TermKind::Group {
+        delim: GroupDelim::None,
+        items: math_ast.exprs().filter_map(|e| lower_expr(e, world.upcast())).collect(),
+    };
+
+    let result = TermResult {
+        term: Some(Term::new(term, math_ast.span(), world.upcast())),
+    };
+
+    serde_json::to_string(&result).unwrap_or_else(|_| r#"{"error":"JSON serialization failed"}"#.to_string())
+}
+
+/// The direct child expressions of `expr`, in the same math-relevant subset
+/// `collect_expr_spans`/`MathVisitor` recurse into. Shared by the
+/// structural search (`rewrite_math`) and structural equality
+/// (`compare_math`) helpers below.
+fn direct_children(expr: typst::syntax::ast::Expr) -> Vec<typst::syntax::ast::Expr> {
+    use typst::syntax::ast::{Arg, ArrayItem, DictItem, Expr};
+
+    match expr {
+        Expr::Math(math) => math.exprs().collect(),
+        Expr::MathFrac(frac) => vec![frac.num(), frac.denom()],
+        Expr::MathAttach(attach) => {
+            let mut children = vec![attach.base()];
+            children.extend(attach.bottom());
+            children.extend(attach.top());
+            children
+        }
+        Expr::MathRoot(root) => vec![root.radicand()],
+        Expr::MathDelimited(delim) => delim.body().exprs().collect(),
+        Expr::FuncCall(call) => {
+            let mut children = vec![call.callee()];
+            children.extend(call.args().items().filter_map(|arg| match arg {
+                Arg::Pos(expr) => Some(expr),
+                _ => None,
+            }));
+            children
+        }
+        Expr::Parenthesized(paren) => vec![paren.expr()],
+        Expr::Array(array) => array
+            .items()
+            .map(|item| match item {
+                ArrayItem::Pos(expr) => expr,
+                ArrayItem::Spread(spread) => spread.expr(),
+            })
+            .collect(),
+        Expr::Dict(dict) => dict
+            .items()
+            .map(|item| match item {
+                DictItem::Named(named) => named.expr(),
+                DictItem::Keyed(keyed) => keyed.expr(),
+                DictItem::Spread(spread) => spread.expr(),
+            })
+            .collect(),
+        Expr::ContentBlock(block) => block.body().exprs().collect(),
+        Expr::Binary(binary) => vec![binary.lhs(), binary.rhs()],
+        Expr::Unary(unary) => vec![unary.expr()],
+        _ => Vec::new(),
+    }
+}
+
+/// Find the subexpression whose source range is exactly `start..end`,
+/// together with its immediate structural parent (used to judge operator
+/// precedence when splicing a replacement in).
+fn find_expr_by_range(
+    math: typst::syntax::ast::Math,
+    start: usize,
+    end: usize,
+    world: &dyn World,
+) -> Option<(typst::syntax::ast::Expr, Option<typst::syntax::ast::Expr>)> {
+    let mut stack: Vec<(typst::syntax::ast::Expr, Option<typst::syntax::ast::Expr>)> =
+        math.exprs().map(|expr| (expr, None)).collect();
+    while let Some((expr, parent)) = stack.pop() {
+        if let Some(range) = world.range(expr.span()) {
+            if range.start == start && range.end == end {
+                return Some((expr, parent));
+            }
+        }
+        stack.extend(direct_children(expr).into_iter().map(|child| (child, Some(expr))));
+    }
+    None
+}
+
+/// A conservative, common-operator precedence table: higher binds tighter.
+/// Operators we don't specifically recognize (e.g. assignment) fall back to
+/// the loosest precedence, which defaults `needs_parens` to the safe choice
+/// of wrapping rather than risking a silently wrong substitution.
+fn binop_precedence(op: typst::syntax::ast::BinOp) -> u8 {
+    use typst::syntax::ast::BinOp;
+
+    match op {
+        BinOp::Mul | BinOp::Div => 5,
+        BinOp::Add | BinOp::Sub => 4,
+        BinOp::Eq | BinOp::Neq | BinOp::Lt | BinOp::Leq | BinOp::Gt | BinOp::Geq => 3,
+        BinOp::And => 2,
+        BinOp::Or => 1,
+        _ => 0,
+    }
+}
+
+/// Parse `replacement` as a standalone math expression, for precedence
+/// comparison against the site it's being spliced into. `None` if it
+/// doesn't parse cleanly as exactly one expression.
+fn parse_replacement_expr(replacement: &str) -> Option<typst::syntax::ast::Expr> {
+    let file_id = FileId::new(None, VirtualPath::new("replacement.typ"));
+    let mut root = parse_math(replacement);
+    root.numberize(file_id, Span::FULL).ok()?;
+    if !root.errors().is_empty() {
+        return None;
+    }
+    let math = root.cast::<typst::syntax::ast::Math>()?;
+    let mut exprs = math.exprs();
+    let only = exprs.next()?;
+    if exprs.next().is_some() {
+        return None;
+    }
+    Some(only)
+}
+
+/// Parent slots that aren't already delimited by explicit syntax (parens,
+/// braces, a function call's argument list, ...) and so must not receive
+/// an unwrapped, non-atomic replacement: e.g. splicing `p+q` into the
+/// superscript of `x^a` gives `x^p+q`, which re-parses as `(x^p)+q` — the
+/// `+q` escapes the exponent entirely. Likewise splicing `a+b` for the
+/// operand of `-x` gives `-a+b`, which re-parses as `(-a)+b` instead of
+/// `-(a+b)`.
+fn parent_requires_atomic_child(parent: typst::syntax::ast::Expr) -> bool {
+    use typst::syntax::ast::Expr;
+    matches!(parent, Expr::MathAttach(_) | Expr::MathFrac(_) | Expr::MathRoot(_) | Expr::Unary(_))
+}
+
+/// Whether `replacement`, parsed standalone, is atomic (as opposed to e.g.
+/// a `Binary`/`Unary` whose operator could bind loosely into whatever
+/// follows it once spliced in unwrapped). Unparseable input is treated as
+/// non-atomic, the safe default.
+fn is_atomic_replacement(replacement: &str) -> bool {
+    use typst::syntax::ast::Expr;
+    !matches!(parse_replacement_expr(replacement), Some(Expr::Binary(_)) | Some(Expr::Unary(_)) | None)
+}
+
+/// Whether `replacement` needs wrapping in parentheses to stay
+/// mathematically faithful once spliced into `parent`'s slot.
+fn needs_parens(parent: Option<typst::syntax::ast::Expr>, replacement: &str) -> bool {
+    use typst::syntax::ast::Expr;
+
+    let Some(parent) = parent else { return false };
+
+    if let Expr::Binary(parent_binary) = parent {
+        return match parse_replacement_expr(replacement) {
+            Some(Expr::Binary(replacement_binary)) => {
+                binop_precedence(replacement_binary.op()) < binop_precedence(parent_binary.op())
+            }
+            None => true,
+            _ => false,
+        };
+    }
+
+    parent_requires_atomic_child(parent) && !is_atomic_replacement(replacement)
+}
+
+/// Structural equality over `Expr`, ignoring spans and normalizing
+/// insignificant whitespace/shorthands (e.g. `->` vs the arrow glyph).
+fn structurally_equal(a: typst::syntax::ast::Expr, b: typst::syntax::ast::Expr, world: &dyn World) -> bool {
+    use typst::syntax::ast::Expr;
+
+    match (a, b) {
+        (Expr::MathIdent(x), Expr::MathIdent(y)) => x.as_str() == y.as_str(),
+        (Expr::Ident(x), Expr::Ident(y)) => x.as_str() == y.as_str(),
+        (Expr::Int(x), Expr::Int(y)) => x.get() == y.get(),
+        (Expr::Float(x), Expr::Float(y)) => x.get() == y.get(),
+        (Expr::MathFrac(x), Expr::MathFrac(y)) => {
+            structurally_equal(x.num(), y.num(), world) && structurally_equal(x.denom(), y.denom(), world)
+        }
+        (Expr::MathAttach(x), Expr::MathAttach(y)) => {
+            structurally_equal(x.base(), y.base(), world)
+                && optional_equal(x.bottom(), y.bottom(), world)
+                && optional_equal(x.top(), y.top(), world)
+        }
+        (Expr::MathPrimes(x), Expr::MathPrimes(y)) => x.count() == y.count(),
+        (Expr::MathRoot(x), Expr::MathRoot(y)) => {
+            x.index() == y.index() && structurally_equal(x.radicand(), y.radicand(), world)
+        }
+        (Expr::MathDelimited(x), Expr::MathDelimited(y)) => {
+            sequence_equal(x.body().exprs(), y.body().exprs(), world)
+        }
+        (Expr::FuncCall(x), Expr::FuncCall(y)) => {
+            structurally_equal(x.callee(), y.callee(), world) && positional_args_equal(x, y, world)
+        }
+        (Expr::Parenthesized(x), Expr::Parenthesized(y)) => structurally_equal(x.expr(), y.expr(), world),
+        (Expr::ContentBlock(x), Expr::ContentBlock(y)) => {
+            sequence_equal(x.body().exprs(), y.body().exprs(), world)
+        }
+        (Expr::Math(x), Expr::Math(y)) => sequence_equal(x.exprs(), y.exprs(), world),
+        (Expr::Binary(x), Expr::Binary(y)) => {
+            x.op() == y.op() && structurally_equal(x.lhs(), y.lhs(), world) && structurally_equal(x.rhs(), y.rhs(), world)
+        }
+        (Expr::Unary(x), Expr::Unary(y)) => x.op() == y.op() && structurally_equal(x.expr(), y.expr(), world),
+        (Expr::MathShorthand(_), Expr::MathShorthand(_))
+        | (Expr::MathAlignPoint(_), Expr::MathAlignPoint(_))
+        | (Expr::Text(_), Expr::Text(_))
+        | (Expr::Str(_), Expr::Str(_))
+        | (Expr::Bool(_), Expr::Bool(_))
+        | (Expr::None(_), Expr::None(_))
+        | (Expr::Auto(_), Expr::Auto(_)) => normalize_token(&span_text(a.span(), world)) == normalize_token(&span_text(b.span(), world)),
+        _ => false,
+    }
+}
+
+fn optional_equal(a: Option<typst::syntax::ast::Expr>, b: Option<typst::syntax::ast::Expr>, world: &dyn World) -> bool {
+    match (a, b) {
+        (Some(x), Some(y)) => structurally_equal(x, y, world),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+fn sequence_equal(
+    a: impl Iterator<Item = typst::syntax::ast::Expr>,
+    b: impl Iterator<Item = typst::syntax::ast::Expr>,
+    world: &dyn World,
+) -> bool {
+    let a: Vec<_> = a.collect();
+    let b: Vec<_> = b.collect();
+    a.len() == b.len() && a.into_iter().zip(b).all(|(x, y)| structurally_equal(x, y, world))
+}
+
+fn positional_args_equal(a: typst::syntax::ast::FuncCall, b: typst::syntax::ast::FuncCall, world: &dyn World) -> bool {
+    use typst::syntax::ast::Arg;
+
+    let pos = |call: typst::syntax::ast::FuncCall| -> Vec<typst::syntax::ast::Expr> {
+        call.args()
+            .items()
+            .filter_map(|arg| match arg {
+                Arg::Pos(expr) => Some(expr),
+                _ => None,
+            })
+            .collect()
+    };
+    sequence_equal(pos(a).into_iter(), pos(b).into_iter(), world)
+}
+
+/// Normalize a math shorthand/leaf's literal text so e.g. `->` and the
+/// arrow glyph it shorthands compare equal.
+fn normalize_token(text: &str) -> String {
+    const SHORTHANDS: &[(&str, &str)] =
+        &[("->", "→"), ("<-", "←"), ("!=", "≠"), ("<=", "≤"), (">=", "≥"), ("...", "…")];
+
+    let trimmed = text.trim();
+    for (shorthand, glyph) in SHORTHANDS {
+        if trimmed == *shorthand {
+            return (*glyph).to_string();
+        }
+    }
+    trimmed.to_string()
+}
+
+/// Find every subexpression in `math` that is structurally equal to
+/// `target`, together with its immediate parent (for precedence), deduped
+/// so nested matches don't produce overlapping splice ranges.
+fn find_structural_matches(
+    math: typst::syntax::ast::Math,
+    target: typst::syntax::ast::Expr,
+    world: &dyn World,
+) -> Vec<(Range<usize>, Option<typst::syntax::ast::Expr>)> {
+    let mut matches = Vec::new();
+    let mut stack: Vec<(typst::syntax::ast::Expr, Option<typst::syntax::ast::Expr>)> =
+        math.exprs().map(|expr| (expr, None)).collect();
+    while let Some((expr, parent)) = stack.pop() {
+        if structurally_equal(expr, target, world) {
+            if let Some(range) = world.range(expr.span()) {
+                matches.push((range, parent));
+            }
+        }
+        stack.extend(direct_children(expr).into_iter().map(|child| (child, Some(expr))));
+    }
+
+    // Keep only the largest match at each position: a structural match
+    // nested entirely inside another match would otherwise produce
+    // overlapping splice ranges.
+    matches.sort_by_key(|(range, _)| std::cmp::Reverse(range.end - range.start));
+    let mut kept: Vec<(Range<usize>, Option<typst::syntax::ast::Expr>)> = Vec::new();
+    for (range, parent) in matches {
+        let contained = kept
+            .iter()
+            .any(|(existing, _)| existing.start <= range.start && range.end <= existing.end);
+        if !contained {
+            kept.push((range, parent));
+        }
+    }
+    kept.sort_by_key(|(range, _)| range.start);
+    kept
+}
+
+/// Structurally substitute `replacement` for `input[target_start..target_end]`
+/// and every other subexpression structurally equal to it, then re-parse
+/// and re-render.
+#[wasm_bindgen]
+pub fn rewrite_math(input: &str, target_start: usize, target_end: usize, replacement: &str) -> String {
+    let (world, math_ast) = match parse_math_ast(input) {
+        Ok(parsed) => parsed,
+        Err(error) => return error_json(error),
+    };
+
+    let Some((target, _)) = find_expr_by_range(math_ast, target_start, target_end, world.upcast()) else {
+        return error_json(ErrorResult::single(Diagnostic::detached(format!(
+            "No subexpression covers the range {}..{}",
+            target_start, target_end
+        ))));
+    };
+
+    let matches = find_structural_matches(math_ast, target, world.upcast());
+    if matches.is_empty() {
+        return error_json(ErrorResult::single(Diagnostic::detached(
+            "Failed to resolve the target subexpression's source range",
+        )));
+    }
+
+    let mut rewritten = input.to_string();
+    for (range, parent) in matches.into_iter().rev() {
+        let piece = if needs_parens(parent, replacement) {
+            format!("({replacement})")
+        } else {
+            replacement.to_string()
+        };
+        rewritten.replace_range(range, &piece);
+    }
+
+    let compiled = match compile_math(&rewritten) {
+        Ok(compiled) => compiled,
+        Err(error) => return error_json(error),
+    };
+
+    let subexpressions = extract_subexpressions(
+        &compiled.math_ast,
+        &rewritten,
+        &compiled.frame,
+        compiled.world.upcast(),
+        Point::zero(),
+    );
+    let svg = typst_svg::svg_frame(&compiled.frame);
+
+    serde_json::to_string(&MathResult { svg, subexpressions })
+        .unwrap_or_else(|_| r#"{"error":"JSON serialization failed"}"#.to_string())
+}
+
+/// A pair of corresponding source ranges on either side of a `compare_math`
+/// call, one per AST node both sides agreed on.
+#[derive(Serialize, Deserialize)]
+struct RangePair {
+    lhs_start: usize,
+    lhs_end: usize,
+    rhs_start: usize,
+    rhs_end: usize,
+}
+
+/// The deepest point at which two expressions being compared diverge.
+#[derive(Serialize, Deserialize)]
+struct Mismatch {
+    lhs_start: Option<usize>,
+    lhs_end: Option<usize>,
+    rhs_start: Option<usize>,
+    rhs_end: Option<usize>,
+    reason: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EquivalenceResult {
+    equivalent: bool,
+    correspondence: Vec<RangePair>,
+    mismatch: Option<Mismatch>,
+}
+
+fn span_mismatch(
+    lhs_span: Span,
+    rhs_span: Span,
+    lhs_world: &dyn World,
+    rhs_world: &dyn World,
+    reason: &str,
+) -> Mismatch {
+    let lhs_range = lhs_world.range(lhs_span);
+    let rhs_range = rhs_world.range(rhs_span);
+    Mismatch {
+        lhs_start: lhs_range.as_ref().map(|r| r.start),
+        lhs_end: lhs_range.as_ref().map(|r| r.end),
+        rhs_start: rhs_range.as_ref().map(|r| r.start),
+        rhs_end: rhs_range.as_ref().map(|r| r.end),
+        reason: reason.to_string(),
+    }
+}
+
+/// Recurse in lockstep over two `Expr` trees (from possibly different
+/// sources, hence the two `World`s), building a node correspondence list on
+/// success. On the first incompatibility, returns the deepest span pair
+/// that actually diverges rather than the outermost one, so the caller can
+/// point at the specific mismatched token instead of the whole expression.
+fn compare_exprs(
+    lhs: typst::syntax::ast::Expr,
+    rhs: typst::syntax::ast::Expr,
+    lhs_world: &dyn World,
+    rhs_world: &dyn World,
+    commutative: &HashSet<String>,
+) -> Result<Vec<RangePair>, Mismatch> {
+    use typst::syntax::ast::Expr;
+
+    let mut correspondence: Vec<RangePair> = match (lhs, rhs) {
+        (Expr::MathIdent(x), Expr::MathIdent(y)) => {
+            if x.as_str() != y.as_str() {
+                return Err(span_mismatch(lhs.span(), rhs.span(), lhs_world, rhs_world, "identifiers differ"));
+            }
+            Vec::new()
+        }
+        (Expr::Ident(x), Expr::Ident(y)) => {
+            if x.as_str() != y.as_str() {
+                return Err(span_mismatch(lhs.span(), rhs.span(), lhs_world, rhs_world, "identifiers differ"));
+            }
+            Vec::new()
+        }
+        (Expr::Int(x), Expr::Int(y)) => {
+            if x.get() != y.get() {
+                return Err(span_mismatch(lhs.span(), rhs.span(), lhs_world, rhs_world, "integer literals differ"));
+            }
+            Vec::new()
+        }
+        (Expr::Float(x), Expr::Float(y)) => {
+            if x.get() != y.get() {
+                return Err(span_mismatch(lhs.span(), rhs.span(), lhs_world, rhs_world, "float literals differ"));
+            }
+            Vec::new()
+        }
+        (Expr::MathAttach(x), Expr::MathAttach(y)) => {
+            let mut c = compare_exprs(x.base(), y.base(), lhs_world, rhs_world, commutative)?;
+            c.extend(compare_optional(
+                x.bottom(), y.bottom(), lhs_world, rhs_world, commutative,
+                x.span(), y.span(), "subscript presence differs",
+            )?);
+            c.extend(compare_optional(
+                x.top(), y.top(), lhs_world, rhs_world, commutative,
+                x.span(), y.span(), "superscript presence differs",
+            )?);
+            c
+        }
+        (Expr::MathPrimes(x), Expr::MathPrimes(y)) => {
+            if x.count() != y.count() {
+                return Err(span_mismatch(lhs.span(), rhs.span(), lhs_world, rhs_world, "prime counts differ"));
+            }
+            Vec::new()
+        }
+        (Expr::MathRoot(x), Expr::MathRoot(y)) => {
+            if x.index() != y.index() {
+                return Err(span_mismatch(lhs.span(), rhs.span(), lhs_world, rhs_world, "root indices differ"));
+            }
+            compare_exprs(x.radicand(), y.radicand(), lhs_world, rhs_world, commutative)?
+        }
+        (Expr::MathDelimited(x), Expr::MathDelimited(y))
+            if normalize_token(&span_text(x.open().span(), lhs_world))
+                != normalize_token(&span_text(y.open().span(), rhs_world))
+                || normalize_token(&span_text(x.close().span(), lhs_world))
+                    != normalize_token(&span_text(y.close().span(), rhs_world)) =>
+        {
+            return Err(span_mismatch(lhs.span(), rhs.span(), lhs_world, rhs_world, "delimiters differ"));
+        }
+        // Beyond the delimiters checked above, these all reduce to "same
+        // kind, so compare `direct_children` in order". A `Dict`'s key names
+        // and a `FuncCall`'s named arguments are not compared.
+        (Expr::Math(_), _)
+        | (Expr::MathFrac(_), _)
+        | (Expr::MathDelimited(_), _)
+        | (Expr::ContentBlock(_), _)
+        | (Expr::Parenthesized(_), _)
+        | (Expr::Array(_), _)
+        | (Expr::Dict(_), _)
+        | (Expr::FuncCall(_), _)
+            if std::mem::discriminant(&lhs) == std::mem::discriminant(&rhs) =>
+        {
+            compare_sequences(
+                direct_children(lhs), direct_children(rhs),
+                lhs_world, rhs_world, commutative, lhs.span(), rhs.span(), "substructure counts differ",
+            )?
+        }
+        (Expr::Binary(x), Expr::Binary(y)) => {
+            if x.op() != y.op() {
+                return Err(span_mismatch(lhs.span(), rhs.span(), lhs_world, rhs_world, "operators differ"));
+            }
+            let straight = (|| -> Result<Vec<RangePair>, Mismatch> {
+                let mut c = compare_exprs(x.lhs(), y.lhs(), lhs_world, rhs_world, commutative)?;
+                c.extend(compare_exprs(x.rhs(), y.rhs(), lhs_world, rhs_world, commutative)?);
+                Ok(c)
+            })();
+            match straight {
+                Ok(c) => c,
+                Err(straight_err) if commutative.contains(x.op().as_str()) => {
+                    let swapped = (|| -> Result<Vec<RangePair>, Mismatch> {
+                        let mut c = compare_exprs(x.lhs(), y.rhs(), lhs_world, rhs_world, commutative)?;
+                        c.extend(compare_exprs(x.rhs(), y.lhs(), lhs_world, rhs_world, commutative)?);
+                        Ok(c)
+                    })();
+                    // Commutative operator: fall back to the straight-order
+                    // mismatch if swapping the operands doesn't help either.
+                    swapped.map_err(|_| straight_err)?
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        (Expr::Unary(x), Expr::Unary(y)) => {
+            if x.op() != y.op() {
+                return Err(span_mismatch(lhs.span(), rhs.span(), lhs_world, rhs_world, "operators differ"));
+            }
+            compare_exprs(x.expr(), y.expr(), lhs_world, rhs_world, commutative)?
+        }
+        (Expr::MathShorthand(_), Expr::MathShorthand(_))
+        | (Expr::MathAlignPoint(_), Expr::MathAlignPoint(_))
+        | (Expr::Text(_), Expr::Text(_))
+        | (Expr::Str(_), Expr::Str(_))
+        | (Expr::Bool(_), Expr::Bool(_))
+        | (Expr::None(_), Expr::None(_))
+        | (Expr::Auto(_), Expr::Auto(_)) => {
+            let lhs_text = normalize_token(&span_text(lhs.span(), lhs_world));
+            let rhs_text = normalize_token(&span_text(rhs.span(), rhs_world));
+            if lhs_text != rhs_text {
+                return Err(span_mismatch(lhs.span(), rhs.span(), lhs_world, rhs_world, "tokens differ"));
+            }
+            Vec::new()
+        }
+        _ => return Err(span_mismatch(lhs.span(), rhs.span(), lhs_world, rhs_world, "expression kinds differ")),
+    };
+
+    if let (Some(lr), Some(rr)) = (lhs_world.range(lhs.span()), rhs_world.range(rhs.span())) {
+        correspondence.push(RangePair { lhs_start: lr.start, lhs_end: lr.end, rhs_start: rr.start, rhs_end: rr.end });
+    }
+    Ok(correspondence)
+}
+
+fn compare_optional(
+    lhs: Option<typst::syntax::ast::Expr>,
+    rhs: Option<typst::syntax::ast::Expr>,
+    lhs_world: &dyn World,
+    rhs_world: &dyn World,
+    commutative: &HashSet<String>,
+    lhs_fallback: Span,
+    rhs_fallback: Span,
+    reason: &str,
+) -> Result<Vec<RangePair>, Mismatch> {
+    match (lhs, rhs) {
+        (Some(l), Some(r)) => compare_exprs(l, r, lhs_world, rhs_world, commutative),
+        (None, None) => Ok(Vec::new()),
+        _ => Err(span_mismatch(lhs_fallback, rhs_fallback, lhs_world, rhs_world, reason)),
+    }
+}
+
+fn compare_sequences(
+    lhs_items: Vec<typst::syntax::ast::Expr>,
+    rhs_items: Vec<typst::syntax::ast::Expr>,
+    lhs_world: &dyn World,
+    rhs_world: &dyn World,
+    commutative: &HashSet<String>,
+    lhs_fallback: Span,
+    rhs_fallback: Span,
+    length_mismatch_reason: &str,
+) -> Result<Vec<RangePair>, Mismatch> {
+    if lhs_items.len() != rhs_items.len() {
+        return Err(span_mismatch(lhs_fallback, rhs_fallback, lhs_world, rhs_world, length_mismatch_reason));
+    }
+
+    let mut correspondence = Vec::new();
+    for (l, r) in lhs_items.into_iter().zip(rhs_items) {
+        correspondence.extend(compare_exprs(l, r, lhs_world, rhs_world, commutative)?);
+    }
+    Ok(correspondence)
+}
+
+/// Compare two pure math inputs for structural equivalence up to
+/// whitespace, shorthands, and — for the operators listed in
+/// `commutative_ops` (comma-separated, e.g. `"+,*"`) — operand order. On a
+/// match, `correspondence` pairs up every corresponding `(lhs_range,
+/// rhs_range)` node so a UI can draw "these two halves are the same"
+/// links; on a mismatch, `mismatch` points at the deepest pair of spans
+/// that actually diverged.
+#[wasm_bindgen]
+pub fn compare_math(lhs: &str, rhs: &str, commutative_ops: &str) -> String {
+    let (lhs_world, lhs_ast) = match parse_math_ast(lhs) {
+        Ok(parsed) => parsed,
+        Err(error) => return error_json(error),
+    };
+    let (rhs_world, rhs_ast) = match parse_math_ast(rhs) {
+        Ok(parsed) => parsed,
+        Err(error) => return error_json(error),
+    };
+
+    let commutative: HashSet<String> = commutative_ops
+        .split(',')
+        .map(|op| op.trim().to_string())
+        .filter(|op| !op.is_empty())
+        .collect();
+
+    let result = compare_sequences(
+        lhs_ast.exprs().collect(),
+        rhs_ast.exprs().collect(),
+        lhs_world.upcast(),
+        rhs_world.upcast(),
+        &commutative,
+        lhs_ast.span(),
+        rhs_ast.span(),
+        "expression counts differ",
+    );
+
+    let equivalence = match result {
+        Ok(correspondence) => EquivalenceResult { equivalent: true, correspondence, mismatch: None },
+        Err(mismatch) => EquivalenceResult { equivalent: false, correspondence: Vec::new(), mismatch: Some(mismatch) },
+    };
+
+    serde_json::to_string(&equivalence).unwrap_or_else(|_| r#"{"error":"JSON serialization failed"}"#.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse `source`, then return the structural parent of the subexpression
+    /// at byte range `start..end` (panics if either lookup fails — these are
+    /// test fixtures, not user input).
+    fn parent_of(source: &str, start: usize, end: usize) -> Option<typst::syntax::ast::Expr> {
+        let (world, math_ast) = parse_math_ast(source).expect("test fixture should parse");
+        let (_, parent) = find_expr_by_range(math_ast, start, end, world.upcast())
+            .expect("test fixture should contain the target range");
+        parent
+    }
+
+    #[test]
+    fn needs_parens_wraps_non_atomic_replacement_in_attach_slot() {
+        // "x^a": the superscript `a` is byte range 2..3, parented by the attach node.
+        let parent = parent_of("x^a", 2, 3);
+        assert!(needs_parens(parent, "p+q"));
+        assert!(!needs_parens(parent, "p"));
+    }
+
+    #[test]
+    fn needs_parens_wraps_non_atomic_replacement_in_unary_operand_slot() {
+        // "-x": the operand `x` is byte range 1..2, parented by the unary node.
+        let parent = parent_of("-x", 1, 2);
+        assert!(needs_parens(parent, "a+b"));
+        assert!(!needs_parens(parent, "a"));
+    }
+
+    #[test]
+    fn needs_parens_respects_binary_precedence() {
+        // "a+b": the operand `b` is byte range 2..3, parented by the `+` node.
+        let parent = parent_of("a+b", 2, 3);
+        assert!(!needs_parens(parent, "c*d"), "tighter-binding operator is safe unwrapped");
+        assert!(needs_parens(parent, "c or d"), "looser-binding operator must be wrapped");
+    }
+
+    #[test]
+    fn compare_math_treats_listed_operators_as_order_insensitive() {
+        let result = compare_math("a+b", "b+a", "+");
+        let equivalence: EquivalenceResult = serde_json::from_str(&result).expect("valid JSON");
+        assert!(equivalence.equivalent);
+    }
+
+    #[test]
+    fn compare_math_requires_operand_order_when_operator_not_listed_commutative() {
+        let result = compare_math("a+b", "b+a", "");
+        let equivalence: EquivalenceResult = serde_json::from_str(&result).expect("valid JSON");
+        assert!(!equivalence.equivalent);
+    }
+}